@@ -1,17 +1,426 @@
+use anyhow::{bail, Context, Result};
+use std::path::PathBuf;
+use std::process::Command;
+
 pub fn public_function(x: i32) -> i32 {
     x + 1
 }
 
+#[allow(dead_code)]
 fn private_function() -> &'static str {
     "private"
 }
 
+/// A repository GitNexus keeps mirrored: where to clone it from, and the
+/// remotes it is pushed to afterwards.
+pub struct RepoSpec {
+    pub name: String,
+    pub source_url: String,
+    pub remotes: Vec<String>,
+}
+
 pub struct Config {
     pub name: String,
+    pub repos: Vec<RepoSpec>,
+    pub bind_addr: String,
+    pub webhook_secret: Option<String>,
 }
 
 impl Config {
     pub fn new(name: &str) -> Self {
-        Config { name: name.to_string() }
+        Config {
+            name: name.to_string(),
+            repos: Vec::new(),
+            bind_addr: "127.0.0.1:8080".to_string(),
+            webhook_secret: None,
+        }
+    }
+
+    /// Builds the set of jobs needed to bring every configured repo's mirror up to date.
+    pub fn jobs(&self) -> Vec<job::Job> {
+        self.repos.iter().map(|repo| self.job_for_spec(repo)).collect()
+    }
+
+    /// Builds the job for a single configured repo, or `None` if `repo` is
+    /// not one GitNexus manages.
+    pub fn job_for(&self, repo: &str) -> Option<job::Job> {
+        self.repos.iter().find(|spec| spec.name == repo).map(|spec| self.job_for_spec(spec))
+    }
+
+    fn job_for_spec(&self, repo: &RepoSpec) -> job::Job {
+        let local_path = PathBuf::from(&self.name).join(&repo.name);
+        job::Job::new(repo.name.clone())
+            .with_local_path(local_path)
+            .with_source_url(repo.source_url.clone())
+            .with_remotes(repo.remotes.clone())
+    }
+}
+
+/// A unit of work bound to a single repository: bring the local mirror up to
+/// date, then push it out to every configured destination remote.
+pub mod job {
+    use super::*;
+
+    pub struct Job {
+        pub repo: String,
+        local_path: PathBuf,
+        source_url: String,
+        remotes: Vec<String>,
+    }
+
+    impl Job {
+        /// Creates a job for `repo`. Call `with_local_path`/`with_source_url`/
+        /// `with_remotes` to fill in where it lives, where it clones from,
+        /// and where it gets pushed; `Config::jobs`/`Config::job_for` do
+        /// this for every configured repo.
+        pub fn new(repo: String) -> Self {
+            let local_path = PathBuf::from(&repo);
+            Job { repo, local_path, source_url: String::new(), remotes: Vec::new() }
+        }
+
+        pub fn with_local_path(mut self, local_path: PathBuf) -> Self {
+            self.local_path = local_path;
+            self
+        }
+
+        pub fn with_source_url(mut self, source_url: String) -> Self {
+            self.source_url = source_url;
+            self
+        }
+
+        pub fn with_remotes(mut self, remotes: Vec<String>) -> Self {
+            self.remotes = remotes;
+            self
+        }
+
+        /// Clones or updates the local mirror, then pushes it to every
+        /// configured remote. Independent of any other job: a failure here
+        /// only affects this job's repo.
+        pub fn run(&self) -> Result<()> {
+            if self.local_path.exists() {
+                self.update_mirror()?;
+            } else {
+                self.clone_mirror()?;
+            }
+            for remote in &self.remotes {
+                self.push_mirror(remote)?;
+            }
+            Ok(())
+        }
+
+        fn clone_mirror(&self) -> Result<()> {
+            if self.source_url.is_empty() {
+                bail!("{}: no source URL configured, cannot clone", self.repo);
+            }
+            let status = Command::new("git")
+                .args(["clone", "--mirror", &self.source_url])
+                .arg(&self.local_path)
+                .status()
+                .with_context(|| format!("{}: failed to spawn git clone --mirror", self.repo))?;
+            if !status.success() {
+                bail!("{}: git clone --mirror exited with {status}", self.repo);
+            }
+            Ok(())
+        }
+
+        fn update_mirror(&self) -> Result<()> {
+            // git exits 0 whether or not anything changed, so "already up to
+            // date" needs no special-casing here - only a genuine failure
+            // yields a non-zero exit.
+            let output = Command::new("git")
+                .args(["remote", "update", "--prune"])
+                .current_dir(&self.local_path)
+                .output()
+                .with_context(|| format!("{}: failed to spawn git remote update", self.repo))?;
+            if !output.status.success() {
+                bail!(
+                    "{}: git remote update --prune failed: {}",
+                    self.repo,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                );
+            }
+            Ok(())
+        }
+
+        fn push_mirror(&self, remote: &str) -> Result<()> {
+            // Same as above: "Everything up-to-date" is still exit code 0.
+            let output = Command::new("git")
+                .args(["push", "--mirror", remote])
+                .current_dir(&self.local_path)
+                .output()
+                .with_context(|| format!("{}: failed to spawn git push --mirror to {remote}", self.repo))?;
+            if !output.status.success() {
+                bail!(
+                    "{}: git push --mirror to {remote} failed: {}",
+                    self.repo,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                );
+            }
+            Ok(())
+        }
+    }
+}
+
+/// A deduplicating job queue: at most one `Job` per repo is ever pending or
+/// running. A webhook that arrives while a repo's job is already in flight
+/// just marks it dirty, so the worker re-runs it once more instead of
+/// stacking up duplicate work.
+pub mod queue {
+    use super::job::Job;
+    use std::collections::{HashSet, VecDeque};
+    use std::sync::{Arc, Condvar, Mutex};
+    use std::thread;
+
+    struct State {
+        pending: VecDeque<Job>,
+        queued: HashSet<String>,
+        running: HashSet<String>,
+        dirty: HashSet<String>,
+    }
+
+    pub struct Queue {
+        state: Mutex<State>,
+        not_empty: Condvar,
+    }
+
+    impl Queue {
+        pub fn new() -> Arc<Self> {
+            Arc::new(Queue {
+                state: Mutex::new(State {
+                    pending: VecDeque::new(),
+                    queued: HashSet::new(),
+                    running: HashSet::new(),
+                    dirty: HashSet::new(),
+                }),
+                not_empty: Condvar::new(),
+            })
+        }
+
+        /// Enqueues `job`. If its repo is already running, it is marked dirty
+        /// so it runs again once the current run finishes. If its repo is
+        /// merely pending (not yet popped), the new job is dropped - the
+        /// pending one hasn't run yet and will pick up the latest state.
+        pub fn enqueue(&self, job: Job) {
+            let mut state = self.state.lock().unwrap();
+            if state.running.contains(&job.repo) {
+                state.dirty.insert(job.repo.clone());
+                return;
+            }
+            if state.queued.contains(&job.repo) {
+                return;
+            }
+            state.queued.insert(job.repo.clone());
+            state.pending.push_back(job);
+            self.not_empty.notify_one();
+        }
+
+        fn pop(&self) -> Job {
+            let mut state = self.state.lock().unwrap();
+            loop {
+                if let Some(job) = state.pending.pop_front() {
+                    state.queued.remove(&job.repo);
+                    state.running.insert(job.repo.clone());
+                    return job;
+                }
+                state = self.not_empty.wait(state).unwrap();
+            }
+        }
+
+        /// Marks `job`'s repo as no longer running, re-enqueuing it once more
+        /// if it was dirtied while it ran.
+        fn finish(&self, job: Job) {
+            let mut state = self.state.lock().unwrap();
+            state.running.remove(&job.repo);
+            if state.dirty.remove(&job.repo) {
+                state.queued.insert(job.repo.clone());
+                state.pending.push_back(job);
+                self.not_empty.notify_one();
+            }
+        }
+    }
+
+    /// Spawns the background worker that drains `queue`, running each job to
+    /// completion before picking up the next one.
+    pub fn spawn_worker(queue: Arc<Queue>) -> thread::JoinHandle<()> {
+        thread::spawn(move || loop {
+            let job = queue.pop();
+            if let Err(err) = job.run() {
+                eprintln!("job for {} failed: {err:#}", job.repo);
+            }
+            queue.finish(job);
+        })
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn second_enqueue_while_pending_coalesces_instead_of_stacking() {
+            let queue = Queue::new();
+            queue.enqueue(Job::new("a".to_string()));
+            queue.enqueue(Job::new("a".to_string()));
+
+            let state = queue.state.lock().unwrap();
+            assert_eq!(state.pending.len(), 1);
+            assert!(!state.dirty.contains("a"));
+        }
+
+        #[test]
+        fn enqueue_while_running_dirties_and_reruns_once() {
+            let queue = Queue::new();
+            queue.enqueue(Job::new("a".to_string()));
+            let job = queue.pop();
+
+            queue.enqueue(Job::new("a".to_string()));
+            {
+                let state = queue.state.lock().unwrap();
+                assert!(state.dirty.contains("a"));
+                assert_eq!(state.pending.len(), 0);
+            }
+
+            queue.finish(job);
+            let state = queue.state.lock().unwrap();
+            assert_eq!(state.pending.len(), 1);
+            assert!(!state.dirty.contains("a"));
+        }
+
+        #[test]
+        fn finish_without_dirty_leaves_the_queue_empty() {
+            let queue = Queue::new();
+            queue.enqueue(Job::new("a".to_string()));
+            let job = queue.pop();
+            queue.finish(job);
+
+            let state = queue.state.lock().unwrap();
+            assert_eq!(state.pending.len(), 0);
+            assert!(state.queued.is_empty());
+            assert!(state.running.is_empty());
+        }
+    }
+}
+
+/// The HTTP side: receives push webhooks, authenticates them against the
+/// configured shared secret, and enqueues a `job::Job` for the affected repo
+/// instead of running it synchronously.
+pub mod webhook {
+    use super::queue::Queue;
+    use super::Config;
+    use anyhow::{anyhow, bail, Result};
+    use hmac::{Hmac, Mac};
+    use sha2::Sha256;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::Arc;
+
+    type HmacSha256 = Hmac<Sha256>;
+
+    /// Verifies a GitHub-style `sha256=<hex>` signature header against `secret`.
+    pub fn verify_signature(secret: &str, payload: &[u8], signature_header: &str) -> bool {
+        let Some(hex_sig) = signature_header.strip_prefix("sha256=") else {
+            return false;
+        };
+        let Ok(expected) = hex::decode(hex_sig) else {
+            return false;
+        };
+        let Ok(mut mac) = HmacSha256::new_from_slice(secret.as_bytes()) else {
+            return false;
+        };
+        mac.update(payload);
+        mac.verify_slice(&expected).is_ok()
+    }
+
+    /// Binds `config.bind_addr` and serves push webhooks until the listener
+    /// errors, enqueuing a real `Job` (built the same way `Config::jobs`
+    /// builds one) onto `queue` for each authenticated, known repo.
+    pub fn serve(config: &Config, queue: Arc<Queue>) -> Result<()> {
+        let listener = TcpListener::bind(&config.bind_addr)?;
+        for stream in listener.incoming() {
+            if let Err(err) = handle_connection(stream?, config, &queue) {
+                eprintln!("webhook request failed: {err:#}");
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_connection(stream: TcpStream, config: &Config, queue: &Arc<Queue>) -> Result<()> {
+        let mut reader = BufReader::new(&stream);
+        let headers = read_headers(&mut reader)?;
+        let content_length: usize = header_value(&headers, "Content-Length")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+        let mut body = vec![0u8; content_length];
+        reader.read_exact(&mut body)?;
+
+        let repo = header_value(&headers, "X-Repo-Name")
+            .ok_or_else(|| anyhow!("missing X-Repo-Name header"))?;
+        if let Some(secret) = &config.webhook_secret {
+            let signature = header_value(&headers, "X-Hub-Signature-256")
+                .ok_or_else(|| anyhow!("missing X-Hub-Signature-256 header"))?;
+            if !verify_signature(secret, &body, &signature) {
+                bail!("invalid webhook signature for {repo}");
+            }
+        }
+        let job = config.job_for(&repo).ok_or_else(|| anyhow!("unknown repo {repo}"))?;
+        queue.enqueue(job);
+        (&stream).write_all(b"HTTP/1.1 202 Accepted\r\nContent-Length: 0\r\n\r\n")?;
+        Ok(())
+    }
+
+    /// Reads the request line and headers off `reader`, stopping at the
+    /// blank line that terminates them - without waiting for the client to
+    /// close the connection, which keep-alive senders like GitHub's never do.
+    fn read_headers(reader: &mut BufReader<&TcpStream>) -> Result<String> {
+        let mut headers = String::new();
+        loop {
+            let mut line = String::new();
+            let n = reader.read_line(&mut line)?;
+            if n == 0 || line == "\r\n" || line == "\n" {
+                break;
+            }
+            headers.push_str(&line);
+        }
+        Ok(headers)
+    }
+
+    fn header_value(headers: &str, name: &str) -> Option<String> {
+        headers.lines().find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            key.trim().eq_ignore_ascii_case(name).then(|| value.trim().to_string())
+        })
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn sign(secret: &str, payload: &[u8]) -> String {
+            let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).unwrap();
+            mac.update(payload);
+            format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+        }
+
+        #[test]
+        fn accepts_a_correctly_signed_payload() {
+            let signature = sign("shared-secret", b"push payload");
+            assert!(verify_signature("shared-secret", b"push payload", &signature));
+        }
+
+        #[test]
+        fn rejects_a_tampered_payload() {
+            let signature = sign("shared-secret", b"push payload");
+            assert!(!verify_signature("shared-secret", b"different payload", &signature));
+        }
+
+        #[test]
+        fn rejects_the_wrong_secret() {
+            let signature = sign("shared-secret", b"push payload");
+            assert!(!verify_signature("wrong-secret", b"push payload", &signature));
+        }
+
+        #[test]
+        fn rejects_a_malformed_header() {
+            assert!(!verify_signature("shared-secret", b"push payload", "not-a-signature"));
+        }
     }
 }